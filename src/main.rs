@@ -11,7 +11,9 @@ use std::collections::HashMap;
 use std::env;
 use std::fs::{self, File};
 use std::io::{self, Read};
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
 use std::path::Path;
+use std::time::UNIX_EPOCH;
 
 use serde::{Deserialize, Serialize};
 use serde_json;
@@ -20,19 +22,240 @@ use md5::{Digest as Md5Digest, Md5};
 use sha1::{Sha1};
 use sha2::{Sha256, Sha512};
 
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine as _;
+use glob::Pattern;
+use indicatif::{ProgressBar, ProgressStyle};
+use rayon::prelude::*;
+use walkdir::WalkDir;
+
 #[derive(Debug, Deserialize, Serialize)]
 struct Config {
     directory: String,
     hash_logic: String,
+    #[serde(default)]
+    recursive: bool,
+    #[serde(default)]
+    include: Option<Vec<String>>,
+    #[serde(default)]
+    exclude: Option<Vec<String>>,
+    #[serde(default)]
+    jobs: Option<usize>,
+    #[serde(default)]
+    dedup_action: DedupAction,
+}
+
+/// What to do with the older duplicates once the newest copy of a set of
+/// identical files has been identified.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum DedupAction {
+    #[default]
+    Delete,
+    Hardlink,
+}
+
+/// Removes the older duplicates according to `action`: deletes them outright,
+/// or replaces each with a hard link to the kept file, preserving its path
+/// while collapsing the on-disk content to a single copy.
+fn apply_dedup_action(action: DedupAction, files_to_remove: &[(String, String)]) -> io::Result<()> {
+    for (remove_path, keep_path) in files_to_remove {
+        match action {
+            DedupAction::Delete => {
+                fs::remove_file(remove_path)?;
+            }
+            DedupAction::Hardlink => {
+                let keep_metadata = fs::metadata(keep_path)?;
+                if let Ok(remove_metadata) = fs::metadata(remove_path) {
+                    if remove_metadata.dev() == keep_metadata.dev()
+                        && remove_metadata.ino() == keep_metadata.ino()
+                    {
+                        continue;
+                    }
+                }
+
+                // Link at a temp name in the same directory first, then rename
+                // it over `remove_path`. This way a cross-device `hard_link`
+                // failure (e.g. `keep_path` on a different mount) leaves
+                // `remove_path` untouched instead of deleting it and then
+                // failing to create the replacement.
+                let remove_path = Path::new(remove_path);
+                let parent = remove_path.parent().unwrap_or_else(|| Path::new("."));
+                let file_name = remove_path
+                    .file_name()
+                    .expect("remove_path has no file name")
+                    .to_string_lossy();
+                let temp_path = parent.join(format!(".{}.rclean-tmp", file_name));
+
+                fs::hard_link(keep_path, &temp_path)?;
+                fs::rename(&temp_path, remove_path)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns every regular file under `target_directory`, walking subdirectories
+/// when `config.recursive` is set, and honoring the configured `include`/`exclude`
+/// glob patterns (relative to `target_directory`). `exclude_always` is skipped
+/// unconditionally, regardless of `include`/`exclude` — used to keep the
+/// tool's own `results.json` and config file out of the candidate set.
+fn collect_files(
+    target_directory: &Path,
+    config: &Config,
+    exclude_always: &[&Path],
+) -> io::Result<Vec<String>> {
+    let include_patterns = compile_patterns(&config.include)?;
+    let exclude_patterns = compile_patterns(&config.exclude)?;
+
+    let max_depth = if config.recursive { usize::MAX } else { 1 };
+
+    let mut files = Vec::new();
+    for entry in WalkDir::new(target_directory)
+        .max_depth(max_depth)
+        .into_iter()
+        .filter_map(|e| e.ok())
+    {
+        let path = entry.path();
+        if !path.is_file() {
+            continue;
+        }
+
+        if exclude_always.iter().any(|p| same_file(p, path)) {
+            continue;
+        }
+
+        let relative = path.strip_prefix(target_directory).unwrap_or(path);
+
+        if !include_patterns.is_empty() && !matches_any(&include_patterns, relative) {
+            continue;
+        }
+        if matches_any(&exclude_patterns, relative) {
+            continue;
+        }
+
+        files.push(path.to_str().unwrap().to_string());
+    }
+
+    Ok(files)
+}
+
+/// Compares two paths as the same file, canonicalizing when possible so a
+/// relative config path and its absolute form inside the walk still match.
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+/// Compiles the configured `include`/`exclude` globs, failing loudly on a
+/// malformed pattern rather than silently dropping it from the filter.
+fn compile_patterns(patterns: &Option<Vec<String>>) -> io::Result<Vec<Pattern>> {
+    let Some(patterns) = patterns.as_ref() else {
+        return Ok(Vec::new());
+    };
+
+    patterns
+        .iter()
+        .map(|p| {
+            Pattern::new(p).map_err(|e| {
+                io::Error::new(io::ErrorKind::InvalidInput, format!("invalid glob pattern {:?}: {}", p, e))
+            })
+        })
+        .collect()
+}
+
+fn matches_any(patterns: &[Pattern], path: &Path) -> bool {
+    patterns.iter().any(|pattern| pattern.matches_path(path))
+}
+
+/// A single cached hash result, keyed by path in `results.json` so a later run
+/// can tell whether a file changed since it was last hashed.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct CacheEntry {
+    path: String,
+    size: u64,
+    mtime: u64,
+    hash: String,
+    algo: String,
+}
+
+/// One entry of the `--log` manifest: what happened (or would happen, under
+/// `--dry-run`) to a single file.
+#[derive(Debug, Serialize)]
+struct ManifestEntry {
+    action: String,
+    path: String,
+    hash: String,
+    size: u64,
+    mtime: u64,
+    mode: u32,
+}
+
+impl ManifestEntry {
+    fn new(action: &str, path: &str, hash: &str, metadata: &fs::Metadata) -> io::Result<Self> {
+        Ok(ManifestEntry {
+            action: action.to_string(),
+            path: path.to_string(),
+            hash: hash.to_string(),
+            size: metadata.len(),
+            mtime: file_mtime(metadata)?,
+            mode: metadata.permissions().mode(),
+        })
+    }
+}
+
+fn file_mtime(metadata: &fs::Metadata) -> io::Result<u64> {
+    let duration = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .expect("File modified time is before the Unix epoch");
+    Ok(duration.as_secs())
+}
+
+/// Returns the cached hash for `path` if its size, modified-time and hash
+/// algorithm still match what's on disk, so it can be skipped instead of
+/// re-hashed.
+fn cached_hash(cache: &HashMap<String, CacheEntry>, path: &str, hash_logic: &str) -> Option<String> {
+    let entry = cache.get(path)?;
+    let metadata = fs::metadata(path).ok()?;
+
+    if entry.algo != hash_logic {
+        return None;
+    }
+    if entry.size != metadata.len() {
+        return None;
+    }
+    if entry.mtime != file_mtime(&metadata).ok()? {
+        return None;
+    }
+
+    Some(entry.hash.clone())
 }
 
 fn main() -> io::Result<()> {
     let args: Vec<String> = env::args().collect();
-    let config_file = if args.len() > 1 {
-        args[1].clone()
-    } else {
-        "config.json".to_string()
-    };
+    let mut config_file = "config.json".to_string();
+    let mut verify_mode = false;
+    let mut dry_run = false;
+    let mut log_path: Option<String> = None;
+    let mut args_iter = args[1..].iter();
+    while let Some(arg) = args_iter.next() {
+        match arg.as_str() {
+            "--verify" => verify_mode = true,
+            "--dry-run" => dry_run = true,
+            "--log" => {
+                log_path = Some(
+                    args_iter
+                        .next()
+                        .expect("--log requires a path argument")
+                        .clone(),
+                )
+            }
+            other => config_file = other.to_string(),
+        }
+    }
 
     let config: Config = if Path::new(&config_file).exists() {
         let file = File::open(&config_file)?;
@@ -41,78 +264,323 @@ fn main() -> io::Result<()> {
         Config {
             directory: ".".to_string(),
             hash_logic: "MD5".to_string(),
+            recursive: false,
+            include: None,
+            exclude: None,
+            jobs: None,
+            dedup_action: DedupAction::Delete,
         }
     };
 
     let target_directory = Path::new(&config.directory);
 
     let hash_results_file = target_directory.join("results.json");
-    let mut file_hashes: HashMap<String, String> = if hash_results_file.exists() {
-        let file = File::open(hash_results_file)?;
-        serde_json::from_reader(file).expect("Error parsing results file")
+    let cache: HashMap<String, CacheEntry> = if hash_results_file.exists() {
+        let file = File::open(&hash_results_file)?;
+        // An older results.json may use the pre-chunk0-4 schema; treat it as
+        // absent rather than panicking so upgrading users just rebuild the cache.
+        serde_json::from_reader(file).unwrap_or_default()
     } else {
         HashMap::new()
     };
 
-    let mut files_to_remove = Vec::new();
+    if verify_mode {
+        run_verify(&cache);
+        return Ok(());
+    }
 
-    for entry in fs::read_dir(target_directory)? {
-        let entry = entry?;
-        let path = entry.path();
-        if path.is_file() {
-            let file_hash = compute_hash(&path, &config.hash_logic);
+    let candidates = collect_files(
+        target_directory,
+        &config,
+        &[&hash_results_file, Path::new(&config_file)],
+    )?;
 
-            if let Some(existing_file) = file_hashes.get(&file_hash) {
-                let existing_metadata = fs::metadata(existing_file)?;
-                let current_metadata = fs::metadata(&path)?;
+    let mut hashed: Vec<(String, String)> = Vec::with_capacity(candidates.len());
+    let mut to_hash: Vec<&String> = Vec::new();
+    for path in &candidates {
+        match cached_hash(&cache, path, &config.hash_logic) {
+            Some(hash) => hashed.push((path.clone(), hash)),
+            None => to_hash.push(path),
+        }
+    }
 
-                if current_metadata.modified()? > existing_metadata.modified()? {
-                    files_to_remove.push(existing_file.clone());
-                } else {
-                    files_to_remove.push(path.to_str().unwrap().to_string());
-                }
+    let jobs = config.jobs.unwrap_or_else(|| {
+        std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1)
+    });
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(jobs)
+        .build()
+        .expect("Failed to build thread pool");
+
+    let progress = ProgressBar::new(to_hash.len() as u64);
+    progress.set_style(
+        ProgressStyle::default_bar()
+            .template("{bar:40.cyan/blue} {pos}/{len} {msg}")
+            .unwrap(),
+    );
+
+    let freshly_hashed: Vec<(String, String)> = pool.install(|| {
+        to_hash
+            .par_iter()
+            .map(|path| {
+                let hash = compute_hash(Path::new(path.as_str()), &config.hash_logic);
+                progress.inc(1);
+                ((*path).clone(), hash)
+            })
+            .collect()
+    });
+    progress.finish_with_message("hashing complete");
+    hashed.extend(freshly_hashed);
+
+    let mut file_hashes: HashMap<String, String> = HashMap::new();
+    let mut files_to_remove: Vec<(String, String)> = Vec::new();
+
+    for (path, file_hash) in &hashed {
+        let path = Path::new(path);
+
+        if let Some(existing_file) = file_hashes.get(file_hash).cloned() {
+            let existing_metadata = fs::metadata(&existing_file)?;
+            let current_metadata = fs::metadata(path)?;
+
+            if current_metadata.modified()? > existing_metadata.modified()? {
+                files_to_remove.push((existing_file, file_hash.clone()));
+                file_hashes.insert(file_hash.clone(), path.to_str().unwrap().to_string());
             } else {
-                file_hashes.insert(file_hash, path.to_str().unwrap().to_string());
+                files_to_remove.push((path.to_str().unwrap().to_string(), file_hash.clone()));
             }
+        } else {
+            file_hashes.insert(file_hash.clone(), path.to_str().unwrap().to_string());
         }
     }
 
-    for file in files_to_remove {
-        fs::remove_file(file)?;
+    // Resolve each removal against the final winner for its hash (not whichever
+    // file happened to be the winner when the removal was recorded), so chained
+    // duplicate groups collapse to a single surviving inode under `hardlink`.
+    let files_to_remove: Vec<(String, String)> = files_to_remove
+        .into_iter()
+        .map(|(remove_path, file_hash)| {
+            let keep_path = file_hashes[&file_hash].clone();
+            (remove_path, keep_path)
+        })
+        .collect();
+
+    let removed: std::collections::HashSet<&String> =
+        files_to_remove.iter().map(|(remove_path, _)| remove_path).collect();
+
+    let remove_label = match config.dedup_action {
+        DedupAction::Delete => "remove",
+        DedupAction::Hardlink => "hardlink",
+    };
+
+    let mut manifest = Vec::with_capacity(hashed.len());
+    for (path, hash) in &hashed {
+        let metadata = fs::metadata(path)?;
+        let action = if removed.contains(path) { remove_label } else { "keep" };
+        manifest.push(ManifestEntry::new(action, path, hash, &metadata)?);
+    }
+
+    if dry_run {
+        for entry in &manifest {
+            println!("{}\t{}\t{}", entry.action, entry.path, entry.hash);
+        }
+    } else {
+        apply_dedup_action(config.dedup_action, &files_to_remove)?;
     }
 
-    let results_file = File::create(target_directory.join("results.json"))?;
-    serde_json::to_writer(results_file, &file_hashes)?;
+    if let Some(log_path) = &log_path {
+        let log_file = File::create(log_path)?;
+        serde_json::to_writer_pretty(log_file, &manifest)?;
+    }
+
+    if dry_run {
+        return Ok(());
+    }
+
+    let new_cache: HashMap<String, CacheEntry> = hashed
+        .into_iter()
+        .filter(|(path, _)| config.dedup_action == DedupAction::Hardlink || !removed.contains(path))
+        .filter_map(|(path, hash)| {
+            let metadata = fs::metadata(&path).ok()?;
+            Some((
+                path.clone(),
+                CacheEntry {
+                    path,
+                    size: metadata.len(),
+                    mtime: file_mtime(&metadata).ok()?,
+                    hash,
+                    algo: config.hash_logic.clone(),
+                },
+            ))
+        })
+        .collect();
+
+    let results_file = File::create(&hash_results_file)?;
+    serde_json::to_writer(results_file, &new_cache)?;
 
     Ok(())
 }
 
-fn compute_hash(path: &Path, hash_logic: &str) -> String {
-    let mut file = File::open(path).expect("Failed to open file");
-    let mut buffer = Vec::new();
-    file.read_to_end(&mut buffer).expect("Failed to read file");
+const CHUNK_SIZE: usize = 64 * 1024;
 
+/// Feeds `reader` through the configured hash algorithm in fixed-size chunks,
+/// so memory use stays flat regardless of the input's size.
+fn hash_reader<R: Read>(mut reader: R, hash_logic: &str) -> io::Result<Vec<u8>> {
+    let mut buffer = [0u8; CHUNK_SIZE];
+
+    macro_rules! digest {
+        ($hasher:expr) => {{
+            let mut hasher = $hasher;
+            loop {
+                let n = reader.read(&mut buffer)?;
+                if n == 0 {
+                    break;
+                }
+                hasher.update(&buffer[..n]);
+            }
+            hasher.finalize().to_vec()
+        }};
+    }
+
+    let result = match hash_logic {
+        "MD5" => digest!(Md5::new()),
+        "SHA1" => digest!(Sha1::new()),
+        "SHA256" => digest!(Sha256::new()),
+        "SHA512" => digest!(Sha512::new()),
+        _ => panic!("Invalid hash logic specified"),
+    };
+
+    Ok(result)
+}
+
+/// Subresource-Integrity prefix (as used by npm/cacache) for a configured
+/// hash algorithm, e.g. `"SHA256"` -> `"sha256"`.
+fn integrity_prefix(hash_logic: &str) -> &'static str {
     match hash_logic {
-        "MD5" => {
-            let mut hasher = Md5::new();
-            hasher.update(&buffer);
-            format!("{:x}", hasher.finalize())
+        "MD5" => "md5",
+        "SHA1" => "sha1",
+        "SHA256" => "sha256",
+        "SHA512" => "sha512",
+        _ => panic!("Invalid hash logic specified"),
+    }
+}
+
+/// Formats a raw digest as a `sha<bits>-<base64>`-style integrity string.
+fn to_integrity(hash_logic: &str, digest: &[u8]) -> String {
+    format!("{}-{}", integrity_prefix(hash_logic), BASE64.encode(digest))
+}
+
+/// Splits an integrity string back into its algorithm prefix and decoded digest.
+fn from_integrity(integrity: &str) -> Option<(&str, Vec<u8>)> {
+    let (prefix, encoded) = integrity.split_once('-')?;
+    let digest = BASE64.decode(encoded).ok()?;
+    Some((prefix, digest))
+}
+
+/// Maps an integrity prefix back to the `hash_logic` name that produced it.
+fn hash_logic_from_prefix(prefix: &str) -> Option<&'static str> {
+    match prefix {
+        "md5" => Some("MD5"),
+        "sha1" => Some("SHA1"),
+        "sha256" => Some("SHA256"),
+        "sha512" => Some("SHA512"),
+        _ => None,
+    }
+}
+
+/// Re-hashes every file listed in `cache` and reports any mismatch or missing
+/// file, without touching anything on disk.
+fn run_verify(cache: &HashMap<String, CacheEntry>) {
+    let mut failures = 0;
+
+    for (path, entry) in cache {
+        let path = Path::new(path);
+        if !path.exists() {
+            println!("MISSING  {}", path.display());
+            failures += 1;
+            continue;
         }
-        "SHA1" => {
-            let mut hasher = Sha1::new();
-            hasher.update(&buffer);
-            format!("{:x}", hasher.finalize())
+
+        let hash_logic = match from_integrity(&entry.hash).and_then(|(prefix, _)| hash_logic_from_prefix(prefix))
+        {
+            Some(hash_logic) => hash_logic,
+            None => {
+                println!("UNKNOWN  {} (unrecognized integrity string)", path.display());
+                failures += 1;
+                continue;
+            }
+        };
+
+        let current = compute_hash(path, hash_logic);
+        if current != entry.hash {
+            println!(
+                "MISMATCH {} (expected {}, found {})",
+                path.display(),
+                entry.hash,
+                current
+            );
+            failures += 1;
         }
-        "SHA256" => {
-            let mut hasher = Sha256::new();
-            hasher.update(&buffer);
-            format!("{:x}", hasher.finalize())
+    }
+
+    if failures == 0 {
+        println!("{} file(s) verified OK", cache.len());
+    } else {
+        println!("{} file(s) failed verification", failures);
+    }
+}
+
+fn compute_hash(path: &Path, hash_logic: &str) -> String {
+    let file = File::open(path).expect("Failed to open file");
+    let digest = hash_reader(file, hash_logic).expect("Failed to read file");
+    to_integrity(hash_logic, &digest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hash_reader_matches_known_vectors() {
+        let cases: &[(&str, &[u8], &str)] = &[
+            ("MD5", b"", "d41d8cd98f00b204e9800998ecf8427e"),
+            ("MD5", b"abc", "900150983cd24fb0d6963f7d28e17f72"),
+            (
+                "SHA1",
+                b"abc",
+                "a9993e364706816aba3e25717850c26c9cd0d89d",
+            ),
+            (
+                "SHA256",
+                b"abc",
+                "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            ),
+        ];
+
+        for (hash_logic, input, expected_hex) in cases {
+            let digest = hash_reader(*input, hash_logic).unwrap();
+            let hex: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+            assert_eq!(&hex, expected_hex);
         }
-        "SHA512" => {
-            let mut hasher = Sha512::new();
-            hasher.update(&buffer);
-            format!("{:x}", hasher.finalize())
+    }
+
+    #[test]
+    fn hash_reader_spans_multiple_chunks() {
+        let input = vec![0x61u8; CHUNK_SIZE * 2 + 17];
+        let digest = hash_reader(input.as_slice(), "SHA256").unwrap();
+        assert_eq!(digest.len(), 32);
+    }
+
+    #[test]
+    fn integrity_round_trips_through_its_prefix() {
+        for hash_logic in ["MD5", "SHA1", "SHA256", "SHA512"] {
+            let digest = hash_reader(b"hello, rclean".as_slice(), hash_logic).unwrap();
+            let integrity = to_integrity(hash_logic, &digest);
+
+            let (prefix, decoded) = from_integrity(&integrity).unwrap();
+            assert_eq!(decoded, digest);
+            assert_eq!(hash_logic_from_prefix(prefix), Some(hash_logic));
         }
-        _ => panic!("Invalid hash logic specified"),
     }
 }
\ No newline at end of file